@@ -18,11 +18,12 @@
 #![no_main]
 
 use core::fmt::Write as BufWrite;
+use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::{debug, error, info, trace, warn};
 use embassy_executor::Spawner;
 use embassy_futures::{
     join::join,
-    select::{Either, select},
+    select::{Either, Either3, select, select3},
 };
 use embassy_rp::{
     bind_interrupts,
@@ -33,18 +34,27 @@ use embassy_rp::{
     uart::BufferedUartTx,
     usb,
 };
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
 use embassy_time::Duration;
 use embassy_usb::{
-    Builder, Config,
+    Builder, Config, Handler,
+    class::cdc_acm::{self, CdcAcmClass},
     class::web_usb::{Config as WebUsbConfig, State, Url, WebUsb},
     driver::{Driver, Endpoint, EndpointIn, EndpointOut},
     msos::{self, windows_version},
 };
 use embedded_io_async::{Read, Write};
-use heapless::{String, Vec};
+use heapless::String;
 use static_cell::{ConstStaticCell, StaticCell};
 use {defmt_rtt as _, panic_probe as _};
 
+mod packet;
+mod r503;
+mod ring;
+use packet::{Packet, PacketError};
+use ring::RingBuffer;
+
 static TX_BUF_CELL: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
 static RX_BUF_CELL: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
 
@@ -56,13 +66,21 @@ bind_interrupts!(pub struct Irqs {
 // This is a randomly generated GUID to allow clients on Windows to find our device
 const DEVICE_INTERFACE_GUIDS: &[&str] = &["{AFB9A6FB-30BA-44BC-9232-806CFC875321}"];
 
+// Bus power state, shared between the USB `Handler` (which runs off bus events) and
+// the UART relay (which parks while the host has the bus suspended instead of
+// spinning its 10 ms poll against a sensor it is not allowed to drive).
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+static RESUMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
 
-    // Turn on the LED to state that we have power and we are running.
-    let mut led = Output::new(p.PIN_7, Level::Low);
-    led.set_high();
+    // Turn on the LED to state that we have power and we are running. The LED is
+    // owned by the power handler from here on, which drops it when the host
+    // suspends the bus and restores it on resume.
+    let led = Output::new(p.PIN_7, Level::Low);
+    let mut power_handler = PowerHandler::new(led);
 
     // Obtain the RP2350 Serial Number
     let serial = get_serial(embassy_rp::otp::get_chipid().unwrap());
@@ -118,6 +136,7 @@ async fn main(_spawner: Spawner) {
     };
 
     let mut state = State::new();
+    let mut cdc_state = cdc_acm::State::new();
 
     let mut builder = Builder::new(
         driver,
@@ -128,23 +147,24 @@ async fn main(_spawner: Spawner) {
         &mut control_buf,
     );
 
-    // Add the Microsoft OS Descriptor (MSOS/MOD) descriptor.
-    // We tell Windows that this entire device is compatible with the "WINUSB" feature,
-    // which causes it to use the built-in WinUSB driver automatically, which in turn
-    // can be used by libusb/rusb software without needing a custom driver or INF file.
-    // In principle you might want to call msos_feature() just on a specific function,
-    // if your device also has other functions that still use standard class drivers.
+    // React to bus suspend/resume so we can power-gate the UART-backed sensor.
+    builder.handler(&mut power_handler);
+
+    // Add the Microsoft OS Descriptor (MSOS/MOD) descriptor set. The "WINUSB"
+    // compatible-id feature is scoped to the vendor/WebUSB function in
+    // `WebEndpoints::new` (not the whole device) so that the standard CDC-ACM
+    // function keeps binding the inbox serial driver; otherwise WinUSB would claim
+    // the CDC interface and the host would never poll its bulk-IN endpoint.
     builder.msos_descriptor(windows_version::WIN8_1, 0);
-    builder.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINUSB", ""));
-    builder.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
-        "DeviceInterfaceGUIDs",
-        msos::PropertyData::RegMultiSz(DEVICE_INTERFACE_GUIDS),
-    ));
 
     // Create classes on the builder (WebUSB just needs some setup, but doesn't return anything)
     WebUsb::configure(&mut builder, &mut state, &webusb_config);
+    // Add a CDC-ACM (virtual serial) function so the sensor can also be driven from
+    // standard serial tools. Because `config.device_class` is already 0xEF/0x02/0x01
+    // (IAD composite) this coexists with the vendor-specific WebUSB interface above.
+    let cdc = CdcAcmClass::new(&mut builder, &mut cdc_state, webusb_config.max_packet_size);
     // Create some USB bulk endpoints for testing.
-    let mut endpoints = WebEndpoints::new(&mut builder, &webusb_config, uart);
+    let mut endpoints = WebEndpoints::new(&mut builder, &webusb_config, uart, cdc);
 
     // Build the builder.
     let mut usb = builder.build();
@@ -169,6 +189,8 @@ async fn main(_spawner: Spawner) {
 struct WebEndpoints<'d, D: Driver<'d>> {
     usb_tx: D::EndpointIn,
     usb_rx: D::EndpointOut,
+    cdc_tx: cdc_acm::Sender<'d, D>,
+    cdc_rx: cdc_acm::Receiver<'d, D>,
     uart_tx: BufferedUartTx<'static, UART0>,
     uart_rx: BufferedUartRx<'static, UART0>,
 }
@@ -178,8 +200,19 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
         builder: &mut Builder<'d, D>,
         config: &'d WebUsbConfig<'d>,
         uart: uart::BufferedUart<'static, UART0>,
+        cdc: CdcAcmClass<'d, D>,
     ) -> Self {
         let mut func = builder.function(0xff, 0x00, 0x00);
+
+        // Scope the WINUSB compatible-id to this vendor function only, so Windows
+        // loads WinUSB here (usable by libusb/rusb without an INF) while leaving the
+        // sibling CDC-ACM function to the inbox serial driver.
+        func.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINUSB", ""));
+        func.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+            "DeviceInterfaceGUIDs",
+            msos::PropertyData::RegMultiSz(DEVICE_INTERFACE_GUIDS),
+        ));
+
         let mut iface = func.interface();
         let mut alt = iface.alt_setting(0xff, 0x00, 0x00, None);
 
@@ -187,12 +220,17 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
         let usb_tx = alt.endpoint_bulk_in(config.max_packet_size);
         // It's "OUT" of the usb end point, so it's our receiver.
         let usb_rx = alt.endpoint_bulk_out(config.max_packet_size);
+        // Split the CDC-ACM class into its bulk IN/OUT halves so the relay can treat
+        // it as a second, parallel transport to the same UART.
+        let (cdc_tx, cdc_rx) = cdc.split();
         // We split our uart interface into tx and rx parts.
         let (uart_tx, uart_rx) = uart.split();
 
         WebEndpoints {
             usb_tx,
             usb_rx,
+            cdc_tx,
+            cdc_rx,
             uart_tx,
             uart_rx,
         }
@@ -203,14 +241,56 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
         self.usb_rx.wait_enabled().await
     }
 
+    // Fan a UART reply out to every host-facing interface that is actually being
+    // driven. The CDC-ACM IN endpoint is only written when the host has asserted
+    // DTR (i.e. opened the virtual serial port); otherwise a host that enumerates
+    // but never drains the port would fill its buffer and `write_packet().await`
+    // would back-pressure the single relay loop, stalling the WebUSB path too. A
+    // short timeout guards against a host that opens the port and then stops
+    // draining, so one un-drained transport can never block the other.
+    async fn fan_out(&mut self, frame: &[u8]) {
+        match embassy_time::with_timeout(Duration::from_millis(50), self.usb_tx.write(frame)).await
+        {
+            Ok(Ok(..)) => debug!("Sent to WebUSB Successfully."),
+            Ok(Err(e)) => error!("WebUSB Write Error: {}", e),
+            Err(embassy_time::TimeoutError) => {
+                warn!("WebUSB host not draining, dropping reply")
+            }
+        }
+        if self.cdc_tx.dtr() {
+            match embassy_time::with_timeout(
+                Duration::from_millis(50),
+                self.cdc_tx.write_packet(frame),
+            )
+            .await
+            {
+                Ok(Ok(..)) => debug!("Sent to CDC-ACM Successfully."),
+                Ok(Err(e)) => error!("CDC-ACM Write Error: {}", e),
+                Err(embassy_time::TimeoutError) => {
+                    warn!("CDC-ACM host not draining, dropping reply")
+                }
+            }
+        }
+    }
+
     async fn relay_command(&mut self) {
         let mut usb_buf = [0u8; 256];
+        let mut cdc_buf = [0u8; 256];
         let mut uart_buf = [0u8; 256];
-        let mut payload: Vec<u8, 256> = Vec::new();
+        let mut payload: RingBuffer<256> = RingBuffer::new();
 
         loop {
-            match select(
+            // While the host has the bus suspended we must not drive the sensor.
+            // Park on the resume signal instead of spinning the 10 ms UART poll.
+            if SUSPENDED.load(Ordering::Acquire) {
+                debug!("relay parked: bus suspended");
+                RESUMED.wait().await;
+                debug!("relay resumed: bus active");
+            }
+
+            match select3(
                 self.usb_rx.read(&mut usb_buf),
+                self.cdc_rx.read_packet(&mut cdc_buf),
                 embassy_time::with_timeout(
                     Duration::from_millis(10),
                     self.uart_rx.read(&mut uart_buf),
@@ -218,8 +298,8 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
             )
             .await
             {
-                // First is USB Side
-                Either::First(Ok(n)) => {
+                // First is the WebUSB side.
+                Either3::First(Ok(n)) => {
                     let command = &usb_buf[..n];
                     pretty_print(Lvl::Info, "WebUSB -> UART", &command);
 
@@ -229,46 +309,72 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
                         Err(e) => error!("Write Error: {:?}", e),
                     };
                 }
-                Either::First(Err(e)) => {
+                Either3::First(Err(e)) => {
                     error!("WebUSB Read Error: {}", e);
                 }
-                // Second is UART Side
-                Either::Second(Ok(Ok(n))) => {
-                    payload
-                        .extend_from_slice(&uart_buf[..n])
-                        .unwrap_or_else(|_| panic!("payload capacity exceeded"));
-
-                    match whole_packet(&payload, &[0xFF, 0xFF, 0xFF, 0xFF]) {
-                        Ok(len) => {
-                            debug!("whole_packet len: {}", len);
-                            pretty_print(Lvl::Info, "UART -> WebUSB", &payload[..len]);
-
-                            // Send the UART reply back to the WebUSB host.
-                            match self.usb_tx.write(&payload[..len]).await {
-                                Ok(..) => debug!("Sent to WebUSB Successfully."),
-                                Err(e) => error!("Error: {}", e),
-                            };
-                            pretty_print(Lvl::Debug, "WebUSB Write:", &payload[..len]);
-
-                            let total = payload.len();
-                            let remaining = total - len;
-
-                            // slide the leftover bytes [len..total] down to the front
-                            let buf = payload.as_mut_slice();
-                            buf.copy_within(len..total, 0);
-                            // adjust the Vec’s length
-                            payload.truncate(remaining);
-                        }
-                        Err(..) => {}
+                // Second is the CDC-ACM (virtual serial) side.
+                Either3::Second(Ok(n)) => {
+                    let command = &cdc_buf[..n];
+                    pretty_print(Lvl::Info, "CDC-ACM -> UART", &command);
+
+                    match self.uart_tx.write(command).await {
+                        Ok(..) => debug!("Send to UART Successfully."),
+                        Err(e) => error!("Write Error: {:?}", e),
                     };
                 }
-                Either::Second(Ok(Err(uart::Error::Break))) => {
+                Either3::Second(Err(e)) => {
+                    error!("CDC-ACM Read Error: {}", e);
+                }
+                // Third is the UART side.
+                Either3::Third(Ok(Ok(n))) => {
+                    // Append the incoming chunk. A short store means the buffer is
+                    // full of junk that never framed up: resync to the next header
+                    // rather than panicking, then store whatever is left over.
+                    let stored = payload.push_slice(&uart_buf[..n]);
+                    if stored < n {
+                        warn!("reassembly buffer full, resyncing to next header");
+                        payload.resync_to_header(&packet::HEADER);
+                        payload.push_slice(&uart_buf[stored..n]);
+                    }
+
+                    // Drain every complete, checksum-valid frame currently buffered.
+                    loop {
+                        let len = match Packet::parse(payload.peek(), &[0xFF, 0xFF, 0xFF, 0xFF]) {
+                            Ok(pkt) => pkt.frame_len(),
+                            // Nothing more we can do until more bytes arrive.
+                            Err(PacketError::Incomplete) => break,
+                            // Corrupt header or checksum: skip to the next header
+                            // instead of forwarding a bad frame to the host.
+                            Err(PacketError::BadHeader) => {
+                                warn!("dropping frame with bad header");
+                                payload.resync_to_header(&packet::HEADER);
+                                continue;
+                            }
+                            Err(PacketError::ChecksumMismatch) => {
+                                warn!("dropping frame with bad checksum");
+                                payload.resync_to_header(&packet::HEADER);
+                                continue;
+                            }
+                        };
+
+                        // Copy the frame out so the ring borrow is released before
+                        // we advance the read cursor and write to the host interfaces.
+                        let mut frame = [0u8; 256];
+                        frame[..len].copy_from_slice(&payload.peek()[..len]);
+                        payload.consume(len);
+
+                        pretty_print(Lvl::Info, "UART -> Host", &frame[..len]);
+                        self.fan_out(&frame[..len]).await;
+                        pretty_print(Lvl::Debug, "Host Write:", &frame[..len]);
+                    }
+                }
+                Either3::Third(Ok(Err(uart::Error::Break))) => {
                     // Normal for UART operations.
                 }
-                Either::Second(Ok(Err(e))) => {
+                Either3::Third(Ok(Err(e))) => {
                     error!("UART Error: {}", e);
                 }
-                Either::Second(Err(embassy_time::TimeoutError)) => {
+                Either3::Third(Err(embassy_time::TimeoutError)) => {
                     // We poll UART alot, it not having data is expected.
                 }
             };
@@ -276,6 +382,65 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
     }
 }
 
+/// Device-state handler that power-gates the R503 in step with the USB bus.
+///
+/// The LED doubles as the sensor's "powered" indicator: it is lit while the bus is
+/// live and dropped when the host suspends (or tears down) the link, honoring the
+/// `max_power` budget rather than assuming the link is always up. `SUSPENDED` and
+/// `RESUMED` hand that state to the UART relay so it can park while suspended.
+struct PowerHandler {
+    led: Output<'static>,
+}
+
+impl PowerHandler {
+    fn new(mut led: Output<'static>) -> Self {
+        // Powered and running.
+        led.set_high();
+        PowerHandler { led }
+    }
+
+    /// Drop the power LED and tell the relay to park, so the sensor is no longer driven.
+    fn power_down(&mut self) {
+        self.led.set_low();
+        SUSPENDED.store(true, Ordering::Release);
+    }
+
+    /// Light the power LED and wake the parked relay.
+    fn power_up(&mut self) {
+        self.led.set_high();
+        SUSPENDED.store(false, Ordering::Release);
+        RESUMED.signal(());
+    }
+}
+
+impl Handler for PowerHandler {
+    fn enabled(&mut self, enabled: bool) {
+        if enabled {
+            info!("USB enabled");
+            self.power_up();
+        } else {
+            info!("USB disabled");
+            self.power_down();
+        }
+    }
+
+    fn reset(&mut self) {
+        info!("USB reset");
+        // A reset brings the bus back to an active, addressable state.
+        self.power_up();
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        if suspended {
+            info!("USB suspended, powering down sensor");
+            self.power_down();
+        } else {
+            info!("USB resumed, powering up sensor");
+            self.power_up();
+        }
+    }
+}
+
 #[allow(dead_code)]
 enum Lvl {
     Trace,
@@ -321,43 +486,6 @@ fn pretty_print(level: Lvl, text: &str, bytes: &[u8]) {
     }
 }
 
-/// Looks into the buffer and finds well formed data frames, returning their offset.
-fn whole_packet(buffer: &[u8], address: &[u8; 4]) -> Result<usize, bool> {
-    // Sanity Check (12 bytes is the smallest valid packet)
-    if buffer.len() < 12 {
-        debug!("Not enough data in the buffer.");
-        return Err(false);
-    }
-    // Header
-    if buffer[0..2] != [0xEF, 0x01] {
-        debug!("Header Does Not Match");
-        return Err(false);
-    }
-    // Address
-    if buffer[2..6] != address[..] {
-        debug!("Address Does Not Match");
-        return Err(false);
-    }
-    // PID
-    debug!("PID: {}", buffer[6]);
-    // Length
-    let len = usize::from_be_bytes([0, 0, buffer[7], buffer[8]]);
-    debug!("LEN: {}", len);
-    if len < 3 {
-        debug!("Length is to short.");
-        return Err(false);
-    }
-
-    // The + 9 is from the offset into the packet for the length.
-    if buffer.len() < (len + 9) {
-        debug!("Not a whole packet yet.");
-        return Err(false);
-    }
-
-    // We should have enough data to create a whole frame.
-    return Ok(len + 9);
-}
-
 /// Converts the RP2350's OPT Unique ID into a &str.
 fn get_serial(unique_id: u64) -> &'static str {
     static SERIAL_STRING: StaticCell<[u8; 16]> = StaticCell::new();
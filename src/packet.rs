@@ -0,0 +1,142 @@
+//! R503 packet protocol layer.
+//!
+//! Every frame on the wire looks like:
+//!
+//! ```text
+//! 0xEF 0x01 | addr(4) | pid(1) | length(2, BE) | payload(length-2) | checksum(2, BE)
+//! ```
+//!
+//! `length` is big-endian and counts the payload bytes plus the two checksum
+//! bytes. The checksum is the low 16 bits of the arithmetic sum of the PID byte,
+//! both length bytes and every payload byte, stored big-endian in the final two
+//! bytes. [`Packet::parse`] validates that checksum on receive; [`Packet::command`]
+//! builds outgoing PID `0x01` command packets with an auto-computed checksum so the
+//! firmware can originate requests instead of only relaying them.
+
+use heapless::Vec;
+
+/// Fixed two-byte start-of-frame header.
+pub const HEADER: [u8; 2] = [0xEF, 0x01];
+
+/// Command packet sent from the host to the sensor.
+pub const PID_COMMAND: u8 = 0x01;
+/// Data packet, continued by further data frames until [`PID_END`].
+pub const PID_DATA: u8 = 0x02;
+/// Acknowledge packet returned by the sensor for a command.
+pub const PID_ACK: u8 = 0x07;
+/// Final data packet in a multi-packet data response.
+pub const PID_END: u8 = 0x08;
+
+/// Why a buffer could not be parsed into a [`Packet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketError {
+    /// The header or address did not match an R503 frame for this device, or the
+    /// length field is too small to hold a payload byte and the checksum.
+    BadHeader,
+    /// A well-formed prefix, but not all of the frame has arrived yet.
+    Incomplete,
+    /// The frame is complete but its trailing checksum does not verify.
+    ChecksumMismatch,
+}
+
+/// A parsed or to-be-serialized R503 frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    /// The 4-byte sensor address.
+    pub address: [u8; 4],
+    /// Packet identifier (`PID_*`).
+    pub pid: u8,
+    /// Payload bytes (confirmation code and/or data), excluding the checksum.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Build a host command packet (PID `0x01`) for `address` carrying `payload`.
+    pub fn command(address: [u8; 4], payload: &'a [u8]) -> Self {
+        Packet {
+            address,
+            pid: PID_COMMAND,
+            payload,
+        }
+    }
+
+    /// Total length of this frame once serialized: header + address + pid + length
+    /// + payload + checksum.
+    pub fn frame_len(&self) -> usize {
+        self.payload.len() + 11
+    }
+
+    /// Parse the first frame at the front of `buffer` for the given `address`.
+    ///
+    /// On success the returned [`Packet`] borrows `buffer`; use [`frame_len`] to
+    /// know how many bytes to consume.
+    ///
+    /// [`frame_len`]: Packet::frame_len
+    pub fn parse(buffer: &'a [u8], address: &[u8; 4]) -> Result<Self, PacketError> {
+        // Smallest valid frame is a one-byte payload: 2 + 4 + 1 + 2 + 1 + 2 = 12.
+        if buffer.len() < 12 {
+            return Err(PacketError::Incomplete);
+        }
+        if buffer[0..2] != HEADER || buffer[2..6] != address[..] {
+            return Err(PacketError::BadHeader);
+        }
+
+        let length = u16::from_be_bytes([buffer[7], buffer[8]]) as usize;
+        // `length` counts the payload plus the two checksum bytes.
+        if length < 3 {
+            return Err(PacketError::BadHeader);
+        }
+        let frame_len = length + 9;
+        if buffer.len() < frame_len {
+            return Err(PacketError::Incomplete);
+        }
+
+        let payload_end = 9 + (length - 2);
+        let payload = &buffer[9..payload_end];
+
+        // The checksum covers the PID, both length bytes and the payload.
+        let expected = checksum(buffer[6], [buffer[7], buffer[8]], payload);
+        let actual = u16::from_be_bytes([buffer[payload_end], buffer[payload_end + 1]]);
+        if expected != actual {
+            return Err(PacketError::ChecksumMismatch);
+        }
+
+        Ok(Packet {
+            address: [buffer[2], buffer[3], buffer[4], buffer[5]],
+            pid: buffer[6],
+            payload,
+        })
+    }
+
+    /// Serialize this packet, computing the length and checksum fields.
+    pub fn serialize<const N: usize>(&self) -> Vec<u8, N> {
+        let mut out: Vec<u8, N> = Vec::new();
+        let length = (self.payload.len() + 2) as u16;
+        let length_bytes = length.to_be_bytes();
+
+        // These pushes cannot exceed `N` for any sensibly sized caller buffer; if
+        // they ever do, a field that does not fit is left out whole and the frame
+        // comes out short rather than panicking.
+        let _ = out.extend_from_slice(&HEADER);
+        let _ = out.extend_from_slice(&self.address);
+        let _ = out.push(self.pid);
+        let _ = out.extend_from_slice(&length_bytes);
+        let _ = out.extend_from_slice(self.payload);
+
+        let sum = checksum(self.pid, length_bytes, self.payload);
+        let _ = out.extend_from_slice(&sum.to_be_bytes());
+        out
+    }
+}
+
+/// Low 16 bits of the sum of the PID, the two length bytes and every payload byte.
+fn checksum(pid: u8, length: [u8; 2], payload: &[u8]) -> u16 {
+    let mut sum = pid as u16;
+    sum = sum.wrapping_add(length[0] as u16);
+    sum = sum.wrapping_add(length[1] as u16);
+    for &b in payload {
+        sum = sum.wrapping_add(b as u16);
+    }
+    sum
+}
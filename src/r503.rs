@@ -0,0 +1,232 @@
+//! High-level async driver for the R503 fingerprint sensor.
+//!
+//! [`R503`] layers typed commands over split read/write transport halves — the same
+//! `uart_rx`/`uart_tx` pair [`WebEndpoints`](crate::WebEndpoints) owns, or any other
+//! [`Read`]/[`Write`] halves sharing one error type. Each command serializes a PID
+//! `0x01` packet with
+//! [`Packet::command`], sends it, and awaits the matching ACK (PID `0x07`), whose
+//! first payload byte is decoded into a [`Confirmation`]. Multi-packet data
+//! responses (PID `0x02` frames terminated by PID `0x08`) are surfaced through
+//! [`DataStream`]. The raw byte passthrough used by the browser client is
+//! unaffected — this is an additional, reusable layer, not a replacement.
+
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+
+use crate::packet::{HEADER, PID_ACK, PID_DATA, PID_END, Packet, PacketError};
+use crate::ring::RingBuffer;
+
+/// Instruction byte carried as the first payload element of a command packet.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum Instruction {
+    GetImage = 0x01,
+    Img2Tz = 0x02,
+    Search = 0x04,
+    RegModel = 0x05,
+    Store = 0x06,
+    ReadSysPara = 0x0F,
+}
+
+/// The default character buffer used for feature extraction and matching.
+const CHAR_BUFFER: u8 = 0x01;
+
+/// Confirmation code returned in the first payload byte of an ACK packet.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Confirmation {
+    /// Command executed successfully (`0x00`).
+    Ok,
+    /// Error receiving the data package (`0x01`).
+    PacketReceiveErr,
+    /// No finger on the sensor (`0x02`).
+    NoFinger,
+    /// Failed to enroll the fingerprint image (`0x03`).
+    EnrollFail,
+    /// The two feature templates did not match during enrollment (`0x0A`).
+    EnrollMismatch,
+    /// No matching fingerprint found in the library (`0x09`).
+    NotFound,
+    /// Addressed page is out of the library's range (`0x0B`).
+    BadLocation,
+    /// Error reading or writing flash (`0x18`).
+    FlashErr,
+    /// Any confirmation code not otherwise decoded, preserved verbatim.
+    Other(u8),
+}
+
+impl From<u8> for Confirmation {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => Confirmation::Ok,
+            0x01 => Confirmation::PacketReceiveErr,
+            0x02 => Confirmation::NoFinger,
+            0x03 => Confirmation::EnrollFail,
+            0x09 => Confirmation::NotFound,
+            0x0A => Confirmation::EnrollMismatch,
+            0x0B => Confirmation::BadLocation,
+            0x18 => Confirmation::FlashErr,
+            other => Confirmation::Other(other),
+        }
+    }
+}
+
+/// Errors surfaced by the driver.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The underlying transport failed.
+    Transport(E),
+    /// A frame was received but could not be parsed.
+    Protocol(PacketError),
+    /// A valid frame arrived with an unexpected PID or empty payload.
+    Unexpected,
+}
+
+/// Async driver for the R503, generic over its split read/write transport halves.
+#[allow(dead_code)]
+pub struct R503<R, W> {
+    rx: R,
+    tx: W,
+    address: [u8; 4],
+    ring: RingBuffer<512>,
+}
+
+#[allow(dead_code)]
+impl<R: Read, W: Write<Error = R::Error>> R503<R, W> {
+    /// Create a driver talking to the sensor at `address` over the `tx`/`rx` halves.
+    pub fn new(tx: W, rx: R, address: [u8; 4]) -> Self {
+        R503 {
+            rx,
+            tx,
+            address,
+            ring: RingBuffer::new(),
+        }
+    }
+
+    /// Capture a finger image into the image buffer.
+    pub async fn get_image(&mut self) -> Result<Confirmation, Error<R::Error>> {
+        self.command(&[Instruction::GetImage as u8]).await
+    }
+
+    /// Generate a character file from the image into character `buffer`.
+    pub async fn img2tz(&mut self, buffer: u8) -> Result<Confirmation, Error<R::Error>> {
+        self.command(&[Instruction::Img2Tz as u8, buffer]).await
+    }
+
+    /// Combine the two character buffers into one template in the model buffer.
+    pub async fn reg_model(&mut self) -> Result<Confirmation, Error<R::Error>> {
+        self.command(&[Instruction::RegModel as u8]).await
+    }
+
+    /// Store the model-buffer template at library slot `page_id`.
+    pub async fn store(&mut self, page_id: u16) -> Result<Confirmation, Error<R::Error>> {
+        let page = page_id.to_be_bytes();
+        self.command(&[Instruction::Store as u8, CHAR_BUFFER, page[0], page[1]])
+            .await
+    }
+
+    /// Search the whole library for the template in the character buffer.
+    pub async fn search(&mut self) -> Result<Confirmation, Error<R::Error>> {
+        // Start page 0, across the full library span.
+        self.command(&[
+            Instruction::Search as u8,
+            CHAR_BUFFER,
+            0x00,
+            0x00,
+            0xFF,
+            0xFF,
+        ])
+        .await
+    }
+
+    /// Send the read-system-parameters command. Only the confirmation code is
+    /// returned; the parameter bytes that follow it in the ACK are discarded.
+    pub async fn read_sys_para(&mut self) -> Result<Confirmation, Error<R::Error>> {
+        self.command(&[Instruction::ReadSysPara as u8]).await
+    }
+
+    /// Borrow a stream over a multi-packet data response that follows a command.
+    pub fn data_stream(&mut self) -> DataStream<'_, R, W> {
+        DataStream {
+            sensor: self,
+            done: false,
+        }
+    }
+
+    /// Send a command payload and await its single ACK confirmation code.
+    async fn command(&mut self, payload: &[u8]) -> Result<Confirmation, Error<R::Error>> {
+        self.send(payload).await?;
+        let (pid, resp) = self.recv().await?;
+        if pid != PID_ACK {
+            return Err(Error::Unexpected);
+        }
+        let code = *resp.first().ok_or(Error::Unexpected)?;
+        Ok(Confirmation::from(code))
+    }
+
+    /// Serialize and transmit a PID `0x01` command packet.
+    async fn send(&mut self, payload: &[u8]) -> Result<(), Error<R::Error>> {
+        let frame: Vec<u8, 64> = Packet::command(self.address, payload).serialize();
+        self.tx.write_all(&frame).await.map_err(Error::Transport)
+    }
+
+    /// Receive the next complete, checksum-valid frame, returning its PID and a
+    /// copy of its payload. Garbage that cannot frame up is resynced past.
+    async fn recv(&mut self) -> Result<(u8, Vec<u8, 256>), Error<R::Error>> {
+        let mut buf = [0u8; 64];
+        loop {
+            match Packet::parse(self.ring.peek(), &self.address) {
+                Ok(pkt) => {
+                    let pid = pkt.pid;
+                    let mut payload: Vec<u8, 256> = Vec::new();
+                    let _ = payload.extend_from_slice(pkt.payload);
+                    let len = pkt.frame_len();
+                    self.ring.consume(len);
+                    return Ok((pid, payload));
+                }
+                Err(PacketError::Incomplete) => {
+                    let n = self.rx.read(&mut buf).await.map_err(Error::Transport)?;
+                    // A short store means the ring is full of bytes that never
+                    // frame up (e.g. a corrupt oversized `length`): resync to the
+                    // next header rather than looping forever, then store the rest.
+                    let stored = self.ring.push_slice(&buf[..n]);
+                    if stored < n {
+                        self.ring.resync_to_header(&HEADER);
+                        self.ring.push_slice(&buf[stored..n]);
+                    }
+                }
+                Err(_) => self.ring.resync_to_header(&HEADER),
+            }
+        }
+    }
+}
+
+/// Async stream over a multi-packet data response (PID `0x02` frames terminated by
+/// a single PID `0x08` frame). Obtained from [`R503::data_stream`].
+#[allow(dead_code)]
+pub struct DataStream<'a, R, W> {
+    sensor: &'a mut R503<R, W>,
+    done: bool,
+}
+
+#[allow(dead_code)]
+impl<R: Read, W: Write<Error = R::Error>> DataStream<'_, R, W> {
+    /// Await the next data chunk, or `None` once the terminating frame is consumed.
+    pub async fn next(&mut self) -> Result<Option<Vec<u8, 256>>, Error<R::Error>> {
+        if self.done {
+            return Ok(None);
+        }
+        let (pid, payload) = self.sensor.recv().await?;
+        match pid {
+            PID_DATA => Ok(Some(payload)),
+            PID_END => {
+                self.done = true;
+                Ok(Some(payload))
+            }
+            _ => Err(Error::Unexpected),
+        }
+    }
+}
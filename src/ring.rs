@@ -0,0 +1,104 @@
+//! A minimal byte ring buffer used to reassemble framed sensor packets coming off
+//! the UART. It keeps a contiguous readable window so `Packet::parse` can be called
+//! directly on [`peek`](RingBuffer::peek), advances the read cursor instead of
+//! memmoving after every extracted frame, and never panics on overflow — the caller
+//! resyncs to the next header instead. Used by the UART relay that feeds both the
+//! WebUSB and CDC-ACM interfaces, and by the R503 driver.
+
+/// A fixed-capacity byte ring buffer with head/tail/empty tracking.
+///
+/// The live window `buf[head..tail]` is always contiguous; it is slid back to the
+/// start of the backing store only when an append would otherwise not fit, so the
+/// common path is a plain copy at the tail.
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    /// Index of the first readable byte.
+    head: usize,
+    /// Index one past the last readable byte.
+    tail: usize,
+}
+
+#[allow(dead_code)]
+impl<const N: usize> RingBuffer<N> {
+    /// Create an empty ring buffer.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    /// Number of readable bytes.
+    pub fn len(&self) -> usize {
+        self.tail - self.head
+    }
+
+    /// `true` when there is nothing to read.
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Drop everything, returning to the empty state.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+    }
+
+    /// Append `data`, compacting the live window toward the front first if that is
+    /// what it takes to make room. Returns the number of bytes actually stored; a
+    /// short write means the buffer is full and the caller must drain or resync.
+    pub fn push_slice(&mut self, data: &[u8]) -> usize {
+        if self.head > 0 && N - self.tail < data.len() {
+            // Slide the live window back to the start of the backing store.
+            self.buf.copy_within(self.head..self.tail, 0);
+            self.tail -= self.head;
+            self.head = 0;
+        }
+        let room = N - self.tail;
+        let n = room.min(data.len());
+        self.buf[self.tail..self.tail + n].copy_from_slice(&data[..n]);
+        self.tail += n;
+        n
+    }
+
+    /// A contiguous view of the readable bytes.
+    pub fn peek(&self) -> &[u8] {
+        &self.buf[self.head..self.tail]
+    }
+
+    /// Advance the read cursor past `n` bytes, clamping to the readable length.
+    /// Resets to the empty state once fully drained so later appends start clean.
+    pub fn consume(&mut self, n: usize) {
+        self.head = (self.head + n).min(self.tail);
+        if self.head == self.tail {
+            self.head = 0;
+            self.tail = 0;
+        }
+    }
+
+    /// Drop leading garbage by scanning forward to the next occurrence of `header`.
+    ///
+    /// Used to recover from overflow or a corrupt frame: the byte at the front is
+    /// skipped and the window is advanced to the next header. If no further header
+    /// is found everything is dropped except a possible trailing partial header (a
+    /// prefix of `header` that may be completed by a later append).
+    pub fn resync_to_header(&mut self, header: &[u8]) {
+        let view = self.peek();
+        if let Some(off) = view
+            .windows(header.len())
+            .enumerate()
+            .skip(1)
+            .find_map(|(i, w)| (w == header).then_some(i))
+        {
+            self.consume(off);
+        } else {
+            // Keep the longest suffix that is still a prefix of `header`.
+            let keep = (1..header.len())
+                .rev()
+                .find(|&k| view.ends_with(&header[..k]))
+                .unwrap_or(0);
+            self.consume(view.len() - keep);
+        }
+    }
+}